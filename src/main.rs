@@ -1,12 +1,16 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use std::fs;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::time::Instant;
 
-use llama_cpp::standard_sampler::StandardSampler;
-use llama_cpp::{LlamaModel, LlamaParams, SessionParams};
+use llama_cpp::standard_sampler::{SamplerStage, StandardSampler};
+use llama_cpp::{EmbeddingsParams, LlamaModel, LlamaParams, SessionParams, Token};
 
 /// Generate a Conventional Commit message from a git diff using a local GGUF
 /// model via llama.cpp (Rust bindings: `llama_cpp` 0.3).
@@ -24,18 +28,27 @@ struct Args {
     #[arg(short = 'n', long, default_value_t = 96)]
     max_tokens: usize,
 
-    /// Temperature (kept for future use; default sampler here is deterministic)
+    /// Sampling temperature; higher is more varied, 0 is greedy
     #[arg(short = 't', long, default_value_t = 0.2)]
     temperature: f32,
 
-    /// Top-p nucleus sampling (kept for future use)
+    /// Top-p nucleus sampling cutoff
     #[arg(long, default_value_t = 0.95)]
     top_p: f32,
 
-    /// Top-k sampling (kept for future use; 0 = disabled)
+    /// Top-k sampling (0 = disabled)
     #[arg(long, default_value_t = 40)]
     top_k: i32,
 
+    /// RNG seed for reproducible sampling (omit for a random seed)
+    #[arg(long)]
+    seed: Option<u32>,
+
+    /// Force greedy decoding for fully deterministic output (ignores
+    /// temperature/top-p/top-k).
+    #[arg(long)]
+    deterministic: bool,
+
     /// Context tokens (increase for longer diffs if you have RAM/VRAM)
     #[arg(short = 'c', long, default_value_t = 4096)]
     context: i32,
@@ -47,6 +60,54 @@ struct Args {
     /// If set, print the prompt that is sent to the model
     #[arg(long)]
     show_prompt: bool,
+
+    /// File used to cache the tokenized system prefix so it is not re-tokenized
+    /// each run (the model's KV state is not persisted). Defaults to a file
+    /// under the OS cache directory.
+    #[arg(long, value_name = "FILE")]
+    prompt_cache: Option<PathBuf>,
+
+    /// Disable the prompt cache entirely and always rebuild from scratch.
+    #[arg(long)]
+    no_prompt_cache: bool,
+
+    /// Number of times to re-sample (with a slightly higher temperature) when
+    /// the generated message fails Conventional Commit validation.
+    #[arg(long, default_value_t = 2)]
+    max_retries: usize,
+
+    /// Exit non-zero if no valid Conventional Commit message can be produced.
+    #[arg(long)]
+    strict: bool,
+
+    /// Retrieve the most similar past commits by embedding similarity and
+    /// inject their subject lines as few-shot examples, so the model reuses
+    /// scopes this repo actually uses.
+    #[arg(long)]
+    embeddings: bool,
+
+    /// How many recent commits to consider when `--embeddings` is set.
+    #[arg(long, default_value_t = 100)]
+    embed_history: usize,
+
+    /// How many nearest commits to inject as few-shot examples.
+    #[arg(long, default_value_t = 3)]
+    examples: usize,
+
+    /// Install a `prepare-commit-msg` hook into the current repo's
+    /// `.git/hooks` so `git commit` is pre-filled with a generated message.
+    #[arg(long)]
+    install_hook: bool,
+
+    /// Remove a `prepare-commit-msg` hook previously installed by quack.
+    #[arg(long)]
+    uninstall_hook: bool,
+
+    /// Internal: invoked by the installed hook with the path to the commit
+    /// message file. Runs quack against the staged diff and prepends the
+    /// result into that file.
+    #[arg(long, hide = true, value_name = "FILE")]
+    prepare_commit_message: Option<PathBuf>,
 }
 
 const SYSTEM_INSTRUCTIONS: &str = r#"You are CommitBot, an expert at crafting precise Conventional Commit messages.
@@ -70,58 +131,526 @@ fn read_diff(args: &Args) -> Result<String> {
     Ok(diff)
 }
 
-fn build_prompt(diff: &str) -> String {
+/// The stable prefix of the prompt: everything that does not depend on the
+/// diff. This is the portion whose tokens are cached by `--prompt-cache`; its
+/// evaluated session (KV) state is not cached and is re-decoded every run.
+fn system_prefix() -> String {
     format!(
         r#"<|system|>
 {system}
-<|user|>
+"#,
+        system = SYSTEM_INSTRUCTIONS
+    )
+}
+
+/// Render retrieved few-shot examples as a block placed ahead of the diff.
+fn examples_block(examples: &[String]) -> String {
+    if examples.is_empty() {
+        return String::new();
+    }
+    let mut block = String::from(
+        "Here are subject lines from similar past commits in this repository; \
+         reuse their scopes and style when appropriate:\n",
+    );
+    for subject in examples {
+        block.push_str("- ");
+        block.push_str(subject);
+        block.push('\n');
+    }
+    block.push('\n');
+    block
+}
+
+/// The per-invocation suffix: the user turn that carries the diff, optionally
+/// preceded by retrieved few-shot examples.
+fn build_suffix(diff: &str, examples: &[String]) -> String {
+    format!(
+        r#"<|user|>
 Given the following unified git diff, write a single Conventional Commit message.
 Do not include code fences. Do not include "Message:" or any explanation.
 If the change is trivial (whitespace/comments), reply with "chore: minor housekeeping".
 
-Diff:
+{examples}Diff:
 ```diff
 {diff}
             <|assistant|>
 "#,
-        system = SYSTEM_INSTRUCTIONS,
+        examples = examples_block(examples),
         diff = diff
     )
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// Identity of a cached prefix: bumped whenever the model identity, the
+/// context size, or the system prefix changes, so a stale cache is ignored.
+struct CacheKey {
+    model: String,
+    model_mtime: u64,
+    n_ctx: i32,
+    prefix_hash: u64,
+}
+
+impl CacheKey {
+    fn compute(args: &Args) -> Result<Self> {
+        let meta = fs::metadata(&args.model)
+            .with_context(|| format!("stat {}", args.model.display()))?;
+        let model_mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut hasher = DefaultHasher::new();
+        system_prefix().hash(&mut hasher);
+        Ok(Self {
+            // Model identity, so two models with the same mtime/context can
+            // never restore each other's (vocab-specific) tokens.
+            model: model_tag(args),
+            model_mtime,
+            n_ctx: args.context,
+            prefix_hash: hasher.finish(),
+        })
+    }
+
+    /// Textual header stored ahead of the cached prefix tokens.
+    fn header(&self) -> String {
+        format!(
+            "quack-prompt-cache v1\nmodel={}\nmtime={}\nn_ctx={}\nprefix={:016x}\n\n",
+            self.model, self.model_mtime, self.n_ctx, self.prefix_hash
+        )
+    }
+}
+
+/// Resolve the prompt-cache path: the explicit flag, or a default under the
+/// OS cache directory.
+fn cache_path(args: &Args) -> Option<PathBuf> {
+    if args.no_prompt_cache {
+        return None;
+    }
+    if let Some(path) = &args.prompt_cache {
+        return Some(path.clone());
+    }
+    Some(cache_base()?.join("prompt-cache.bin"))
+}
+
+/// Load the cached prefix tokens if the file exists and its header matches
+/// `key`. Tokens are stored as little-endian `i32`s after the header.
+fn load_cache(path: &Path, key: &CacheKey) -> Option<Vec<Token>> {
+    let bytes = fs::read(path).ok()?;
+    let header = key.header();
+    let prefix = header.as_bytes();
+    if bytes.len() >= prefix.len() && &bytes[..prefix.len()] == prefix {
+        let tokens = bytes[prefix.len()..]
+            .chunks_exact(4)
+            .map(|c| Token(i32::from_le_bytes([c[0], c[1], c[2], c[3]])))
+            .collect();
+        Some(tokens)
+    } else {
+        None
+    }
+}
+
+/// Persist `tokens` behind `key`'s header, creating parent dirs as needed.
+fn store_cache(path: &Path, key: &CacheKey, tokens: &[Token]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    let mut buf = key.header().into_bytes();
+    for token in tokens {
+        buf.extend_from_slice(&token.0.to_le_bytes());
+    }
+    fs::write(path, buf).with_context(|| format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+/// Base directory for quack's on-disk caches (`<cache>/quack`).
+fn cache_base() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    Some(base.join("quack"))
+}
+
+/// Return the embedding vector for `text` using the already-loaded generation
+/// model (llama.cpp runs a dedicated embedding pass internally), so we do not
+/// load a second copy of the model just to embed.
+fn embed(model: &LlamaModel, text: &str) -> Result<Vec<f32>> {
+    let mut vectors = model
+        .embeddings(&[text], EmbeddingsParams::default())
+        .context("extracting embedding")?;
+    vectors.pop().context("model returned no embedding")
+}
+
+/// A short, stable tag identifying the model (path + mtime) so embeddings from
+/// different models never share a cache file.
+fn model_tag(args: &Args) -> String {
+    let mut hasher = DefaultHasher::new();
+    args.model.hash(&mut hasher);
+    if let Ok(meta) = fs::metadata(&args.model) {
+        if let Ok(modified) = meta.modified() {
+            if let Ok(d) = modified.duration_since(std::time::UNIX_EPOCH) {
+                d.as_secs().hash(&mut hasher);
+            }
+        }
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+/// Cosine similarity between two vectors (0 if either is zero or the lengths
+/// differ — a dimension mismatch means the vectors are not comparable).
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let na = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let nb = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na * nb)
+    }
+}
+
+/// The most recent `n` commits as `(hash, subject)` pairs.
+fn recent_commits(n: usize) -> Result<Vec<(String, String)>> {
+    let out = Command::new("git")
+        .args(["log", &format!("-{n}"), "--pretty=format:%H%x09%s"])
+        .output()
+        .context("running git log")?;
+    if !out.status.success() {
+        bail!("git log failed");
+    }
+    let text = String::from_utf8(out.stdout).context("decoding git log")?;
+    Ok(text
+        .lines()
+        .filter_map(|line| {
+            let (hash, subject) = line.split_once('\t')?;
+            Some((hash.to_string(), subject.to_string()))
+        })
+        .collect())
+}
+
+/// A small on-disk cache of commit-subject embeddings. The file is keyed by
+/// model identity (`embeddings-<tag>.tsv`) and records the vector dimension in
+/// a `dim=` header, so vectors from different models or dimensions are never
+/// mixed into `cosine`. Each entry is one `hash\tv0,v1,...` line.
+struct EmbedCache {
+    path: Option<PathBuf>,
+    dim: Option<usize>,
+    vectors: HashMap<String, Vec<f32>>,
+    dirty: bool,
+}
+
+impl EmbedCache {
+    fn load(tag: &str) -> Self {
+        let path = cache_base().map(|b| b.join(format!("embeddings-{tag}.tsv")));
+        let mut vectors = HashMap::new();
+        let mut dim = None;
+        if let Some(path) = &path {
+            if let Ok(text) = fs::read_to_string(path) {
+                for line in text.lines() {
+                    if let Some(rest) = line.strip_prefix("dim=") {
+                        dim = rest.trim().parse().ok();
+                        continue;
+                    }
+                    if let Some((hash, values)) = line.split_once('\t') {
+                        let vec: Vec<f32> =
+                            values.split(',').filter_map(|v| v.parse().ok()).collect();
+                        if !vec.is_empty() {
+                            vectors.insert(hash.to_string(), vec);
+                        }
+                    }
+                }
+            }
+        }
+        Self {
+            path,
+            dim,
+            vectors,
+            dirty: false,
+        }
+    }
+
+    /// Fetch a cached vector only if its dimension matches `dim`.
+    fn get(&self, hash: &str, dim: usize) -> Option<&Vec<f32>> {
+        self.vectors.get(hash).filter(|v| v.len() == dim)
+    }
+
+    fn insert(&mut self, hash: String, vec: Vec<f32>) {
+        self.dim = Some(vec.len());
+        self.vectors.insert(hash, vec);
+        self.dirty = true;
+    }
+
+    fn save(&self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        let mut buf = String::new();
+        if let Some(dim) = self.dim {
+            buf.push_str(&format!("dim={dim}\n"));
+        }
+        for (hash, vec) in &self.vectors {
+            buf.push_str(hash);
+            buf.push('\t');
+            let joined: Vec<String> = vec.iter().map(|v| v.to_string()).collect();
+            buf.push_str(&joined.join(","));
+            buf.push('\n');
+        }
+        fs::write(path, buf).with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Embed the staged diff and the recent commit subjects (reusing `model`),
+/// then return the subject lines of the `--examples` nearest commits by cosine
+/// similarity.
+fn retrieve_examples(model: &LlamaModel, args: &Args, diff: &str) -> Result<Vec<String>> {
+    let commits = recent_commits(args.embed_history)?;
+    if commits.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut cache = EmbedCache::load(&model_tag(args));
+    let diff_vec = embed(model, diff)?;
+    let dim = diff_vec.len();
+
+    let mut scored: Vec<(f32, String)> = Vec::with_capacity(commits.len());
+    for (hash, subject) in &commits {
+        let vec = match cache.get(hash, dim) {
+            Some(vec) => vec.clone(),
+            None => {
+                let vec = embed(model, subject)?;
+                cache.insert(hash.clone(), vec.clone());
+                vec
+            }
+        };
+        scored.push((cosine(&diff_vec, &vec), subject.clone()));
+    }
+    if let Err(e) = cache.save() {
+        eprintln!("warning: could not write embedding cache: {e:#}");
+    }
+
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(scored
+        .into_iter()
+        .take(args.examples)
+        .map(|(_, subject)| subject)
+        .collect())
+}
+
+/// Strip the boilerplate the model sometimes wraps around the message:
+/// ``` code fences and a leading "Message:" label.
+fn sanitize_message(raw: &str) -> String {
+    let mut lines: Vec<String> = raw
+        .lines()
+        .filter(|l| !l.trim_start().starts_with("```"))
+        .map(|l| l.to_string())
+        .collect();
+    if let Some(first) = lines.first_mut() {
+        if let Some(rest) = first.trim_start().strip_prefix("Message:") {
+            *first = rest.trim_start().to_string();
+        }
+    }
+    lines.join("\n").trim().to_string()
+}
+
+/// Crude imperative-mood check on the summary's first word: reject obvious
+/// past-tense ("added") and gerund ("adding") forms. A trailing `s` is not a
+/// reliable signal — plenty of imperative verbs end in one ("address",
+/// "process", "compress", "pass") — so we do not reject on it.
+fn is_imperative(summary: &str) -> bool {
+    let Some(word) = summary.split_whitespace().next() else {
+        return false;
+    };
+    let word = word.to_ascii_lowercase();
+    !(word.ends_with("ing") || word.ends_with("ed"))
+}
 
-    // Load the model
-    let model = LlamaModel::load_from_file(
+/// Validate `message` against the Conventional Commits grammar plus our house
+/// rules: header-line length, a well-formed breaking-change marker, and an
+/// imperative-mood summary. Returns a human-readable reason on failure.
+fn validate_message(message: &str) -> Result<(), String> {
+    // `parse` fills in the type, optional scope, `!`/`BREAKING CHANGE` marker
+    // and the summary (the description only, *not* the full header line).
+    let commit = conventional_commit_parser::parse(message)
+        .map_err(|e| format!("not a Conventional Commit: {e}"))?;
+
+    // The 72-char convention limits the header line (`type(scope)!: summary`),
+    // not the bare summary, so measure the first line of the message itself.
+    let header = message.lines().next().unwrap_or("").trim_end();
+    if header.chars().count() > 72 {
+        return Err(format!(
+            "header line is {} chars (limit 72)",
+            header.chars().count()
+        ));
+    }
+
+    // If the message mentions a breaking change, the parser must have
+    // recognised it as one (via `!` or a `BREAKING CHANGE:` footer); otherwise
+    // the marker is malformed (e.g. "BREAKING-CHANGE" or a stray note).
+    let mentions_breaking =
+        message.contains("BREAKING CHANGE") || message.contains("BREAKING-CHANGE");
+    if mentions_breaking && !commit.is_breaking_change {
+        return Err(
+            "breaking change is not marked per Conventional Commits \
+             (use `!` or a `BREAKING CHANGE:` footer)"
+                .to_string(),
+        );
+    }
+
+    if !is_imperative(&commit.summary) {
+        return Err("summary is not in imperative mood".to_string());
+    }
+    Ok(())
+}
+
+/// Generate a validated Conventional Commit message, re-sampling with a
+/// slightly higher temperature on each validation failure.
+fn generate_commit_message(args: &Args, diff: &str) -> Result<String> {
+    // Load the model once and reuse it across every retry attempt.
+    let model = load_model(args)?;
+    let examples = if args.embeddings {
+        retrieve_examples(&model, args, diff).unwrap_or_else(|e| {
+            eprintln!("warning: embedding retrieval failed: {e:#}");
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+    // Greedy decoding is deterministic, so re-sampling would reproduce the
+    // exact same (invalid) output — retrying is pointless under --deterministic.
+    let attempts = if args.deterministic {
+        if args.max_retries > 0 {
+            eprintln!("note: --deterministic ignores --max-retries (output is identical each time)");
+        }
+        1
+    } else {
+        args.max_retries + 1
+    };
+    let mut last = String::new();
+    for attempt in 0..attempts {
+        let temperature = args.temperature + attempt as f32 * 0.15;
+        let raw = generate(&model, args, diff, temperature, &examples, |_| {})?;
+        let message = sanitize_message(&raw);
+        match validate_message(&message) {
+            Ok(()) => return Ok(message),
+            Err(reason) => {
+                let next = if attempt + 1 < attempts { "; retrying" } else { "" };
+                eprintln!(
+                    "attempt {} produced an invalid message ({reason}){next}",
+                    attempt + 1
+                );
+                last = message;
+            }
+        }
+    }
+    if args.strict {
+        bail!("no valid Conventional Commit message after {attempts} attempts");
+    }
+    eprintln!("warning: emitting best-effort message; it failed validation");
+    Ok(last)
+}
+
+/// Load the GGUF model once so it can be reused across retry attempts;
+/// reloading it per attempt would dominate latency.
+fn load_model(args: &Args) -> Result<LlamaModel> {
+    LlamaModel::load_from_file(
         &args.model,
         LlamaParams {
             ..Default::default()
         },
     )
-    .context("loading model")?;
+    .context("loading model")
+}
 
+/// Generate a commit message for `diff` at the given sampling `temperature`
+/// against an already-loaded `model`, streaming chunks to `sink` as they
+/// arrive. Returns the full generated text.
+fn generate(
+    model: &LlamaModel,
+    args: &Args,
+    diff: &str,
+    temperature: f32,
+    examples: &[String],
+    mut sink: impl FnMut(&str),
+) -> Result<String> {
     // Create a session (context). Note: n_ctx is u32 in 0.3.x.
+    let mut session_params = SessionParams {
+        n_ctx: args.context as u32,
+        ..Default::default()
+    };
+    if let Some(seed) = args.seed {
+        session_params.seed = seed;
+    }
     let mut session = model
-        .create_session(SessionParams {
-            n_ctx: args.context as u32,
-            ..Default::default()
-        })
+        .create_session(session_params)
         .context("creating session")?;
 
-    // Build prompt
-    let diff = read_diff(&args)?;
-    let prompt = build_prompt(&diff);
+    let prefix = system_prefix();
+    let suffix = build_suffix(diff, examples);
     if args.show_prompt {
-        eprintln!("--- PROMPT START ---\n{}\n--- PROMPT END ---", prompt);
+        eprintln!(
+            "--- PROMPT START ---\n{}{}\n--- PROMPT END ---",
+            prefix, suffix
+        );
+    }
+
+    // Restore the cached system-prefix tokens when possible; otherwise evaluate
+    // the prefix and cache its tokens for next time. NOTE: llama_cpp 0.3 exposes
+    // no serialisable KV state, and `set_context_to_tokens` still re-decodes
+    // every prefix token — so this only saves re-tokenising the constant
+    // prefix, not the KV evaluation. It is NOT a llama.cpp `--prompt-cache`
+    // style speedup; the win is negligible.
+    let cache = cache_path(args);
+    let key = CacheKey::compute(args)?;
+    let restored = cache
+        .as_ref()
+        .and_then(|path| load_cache(path, &key))
+        .and_then(|tokens| session.set_context_to_tokens(&tokens).ok().map(|_| ()))
+        .is_some();
+    if !restored {
+        session
+            .advance_context(&prefix)
+            .context("feeding system prefix")?;
+        if let Some(path) = &cache {
+            let tokens = session.context();
+            if let Err(e) = store_cache(path, &key, &tokens) {
+                eprintln!("warning: could not write prompt cache: {e:#}");
+            }
+        }
     }
 
-    // Feed the prompt to the session
-    session.advance_context(prompt).context("feeding prompt")?;
+    // Feed the per-diff suffix on top of the (cached or fresh) prefix state.
+    session
+        .advance_context(&suffix)
+        .context("feeding diff suffix")?;
 
-    // Sampler: 0.3.x exposes a default sampler; builder methods vary by patch level.
-    // If your crate exposes them, you can apply temperature/top-p/top-k here.
-    let sampler = StandardSampler::default();
+    // Sampler: greedy when deterministic, otherwise a standard softmax chain
+    // built from the configured top-k, nucleus top-p and temperature.
+    let sampler = if args.deterministic {
+        StandardSampler::new_greedy()
+    } else {
+        StandardSampler::new_softmax(
+            vec![
+                SamplerStage::RepetitionPenalty {
+                    repetition_penalty: 1.1,
+                    frequency_penalty: 0.0,
+                    presence_penalty: 0.0,
+                    last_n: 64,
+                },
+                SamplerStage::TopK(args.top_k),
+                SamplerStage::TopP(args.top_p),
+                SamplerStage::Temperature(temperature),
+            ],
+            1,
+        )
+    };
 
     // Start completion and stream strings. `start_completing_with` returns Result<CompletionHandle, _>.
     let start = Instant::now();
@@ -133,11 +662,243 @@ fn main() -> Result<()> {
     let mut out = String::new();
     for chunk in stream_iter {
         let chunk: String = chunk;
-        print!("{}", chunk);
-        let _ = io::stdout().flush();
+        sink(&chunk);
         out.push_str(&chunk);
     }
 
     eprintln!("\n(generated in {:.2?})", start.elapsed());
+    Ok(out)
+}
+
+/// Locate the current repo's hooks directory (`.git/hooks`), honouring a
+/// worktree's `.git` file and `core.hooksPath`.
+fn hooks_dir() -> Result<PathBuf> {
+    let out = Command::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .output()
+        .context("running git rev-parse")?;
+    if !out.status.success() {
+        bail!("not inside a git repository");
+    }
+    let path = String::from_utf8(out.stdout)
+        .context("decoding git output")?
+        .trim()
+        .to_string();
+    Ok(PathBuf::from(path))
+}
+
+const HOOK_MARKER: &str = "# installed by quack";
+
+fn install_hook(args: &Args) -> Result<()> {
+    let dir = hooks_dir()?;
+    fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let hook = dir.join("prepare-commit-msg");
+    if hook.exists() {
+        let existing = fs::read_to_string(&hook).unwrap_or_default();
+        if !existing.contains(HOOK_MARKER) {
+            bail!(
+                "{} already exists and was not installed by quack; \
+                 remove it first or edit it by hand",
+                hook.display()
+            );
+        }
+    }
+    let exe = std::env::current_exe().context("locating quack binary")?;
+    // Bake the model path and context/embeddings flags resolved at install
+    // time into the hook, so `git commit` works regardless of whether
+    // LLAMA_MODEL is set in the committer's environment. `model` is required
+    // by clap, so reaching here means it resolved.
+    let model = fs::canonicalize(&args.model).unwrap_or_else(|_| args.model.clone());
+    let mut flags = format!(" --model \"{}\" --context {}", model.display(), args.context);
+    if args.embeddings {
+        flags.push_str(" --embeddings");
+    }
+    let script = format!(
+        r#"#!/bin/sh
+{marker}
+# Skip when a message was already supplied (message/merge/squash sources).
+case "$2" in
+message|merge|squash) exit 0 ;;
+esac
+exec "{quack}"{flags} --prepare-commit-message "$1"
+"#,
+        marker = HOOK_MARKER,
+        quack = exe.display(),
+        flags = flags,
+    );
+    fs::write(&hook, script).with_context(|| format!("writing {}", hook.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(&hook)?.permissions();
+        perms.set_mode(0o755);
+        fs::set_permissions(&hook, perms)?;
+    }
+    eprintln!("installed prepare-commit-msg hook at {}", hook.display());
     Ok(())
 }
+
+fn uninstall_hook() -> Result<()> {
+    let hook = hooks_dir()?.join("prepare-commit-msg");
+    if !hook.exists() {
+        eprintln!("no prepare-commit-msg hook to remove");
+        return Ok(());
+    }
+    let existing = fs::read_to_string(&hook).unwrap_or_default();
+    if !existing.contains(HOOK_MARKER) {
+        bail!(
+            "{} was not installed by quack; leaving it untouched",
+            hook.display()
+        );
+    }
+    fs::remove_file(&hook).with_context(|| format!("removing {}", hook.display()))?;
+    eprintln!("removed prepare-commit-msg hook at {}", hook.display());
+    Ok(())
+}
+
+/// Read the staged diff via `git diff --staged`.
+fn staged_diff() -> Result<String> {
+    let out = Command::new("git")
+        .args(["diff", "--staged"])
+        .output()
+        .context("running git diff --staged")?;
+    if !out.status.success() {
+        bail!("git diff --staged failed");
+    }
+    String::from_utf8(out.stdout).context("decoding git diff output")
+}
+
+/// Generate a message from the staged diff and prepend it into the commit
+/// message file `path`, above any existing content.
+fn prefill_commit_message(args: &Args, path: &Path) -> Result<()> {
+    let diff = staged_diff()?;
+    if diff.trim().is_empty() {
+        return Ok(());
+    }
+    let message = generate_commit_message(args, &diff)?;
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    let combined = format!("{}\n{}", message.trim(), existing);
+    fs::write(path, combined).with_context(|| format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+/// Hook entry point. A `prepare-commit-msg` hook that exits non-zero aborts
+/// the commit, so this deliberately fails open: any error is logged to stderr
+/// and we return `Ok(())` so the editor still opens with the template.
+fn prepare_commit_message(args: &Args, path: &Path) -> Result<()> {
+    if let Err(e) = prefill_commit_message(args, path) {
+        eprintln!("quack: skipping commit-message prefill: {e:#}");
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    if args.install_hook {
+        return install_hook(&args);
+    }
+    if args.uninstall_hook {
+        return uninstall_hook();
+    }
+    if let Some(path) = args.prepare_commit_message.clone() {
+        return prepare_commit_message(&args, &path);
+    }
+
+    let diff = read_diff(&args)?;
+    let message = generate_commit_message(&args, &diff)?;
+    println!("{message}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_strips_fences_and_label() {
+        let raw = "```\nMessage: feat(api): add endpoint\n```";
+        assert_eq!(sanitize_message(raw), "feat(api): add endpoint");
+    }
+
+    #[test]
+    fn sanitize_leaves_clean_message_untouched() {
+        assert_eq!(sanitize_message("fix: correct off-by-one"), "fix: correct off-by-one");
+    }
+
+    #[test]
+    fn imperative_accepts_verbs_ending_in_s() {
+        // Regression: these are imperative and must not be rejected.
+        for verb in ["address", "process", "focus", "pass", "compress", "express"] {
+            assert!(is_imperative(verb), "{verb} should be imperative");
+        }
+        assert!(is_imperative("add logging"));
+    }
+
+    #[test]
+    fn imperative_rejects_past_and_gerund() {
+        assert!(!is_imperative("added logging"));
+        assert!(!is_imperative("adding logging"));
+    }
+
+    #[test]
+    fn validate_accepts_well_formed_message() {
+        assert!(validate_message("feat(parser): add embeddings mode").is_ok());
+        assert!(validate_message("fix!: drop deprecated flag\n\nBREAKING CHANGE: gone").is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_non_conventional() {
+        assert!(validate_message("just some words").is_err());
+    }
+
+    #[test]
+    fn validate_rejects_overlong_header() {
+        let long = format!("feat: {}", "x ".repeat(40));
+        assert!(validate_message(&long).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_unmarked_breaking_change() {
+        // Mentions a breaking change but neither `!` nor a proper footer marks it.
+        let msg = "feat: add flag\n\nthis is a BREAKING CHANGE by the way";
+        assert!(validate_message(msg).is_err());
+    }
+
+    #[test]
+    fn cosine_of_identical_is_one() {
+        let v = [1.0, 2.0, 3.0];
+        assert!((cosine(&v, &v) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cosine_of_orthogonal_is_zero() {
+        assert!(cosine(&[1.0, 0.0], &[0.0, 1.0]).abs() < 1e-6);
+        assert_eq!(cosine(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+    }
+
+    #[test]
+    fn cache_header_round_trips_tokens() {
+        let key = CacheKey {
+            model: "modela".to_string(),
+            model_mtime: 42,
+            n_ctx: 4096,
+            prefix_hash: 0xdead_beef,
+        };
+        let tokens = vec![Token(1), Token(2), Token(65535), Token(-1)];
+        let path = std::env::temp_dir().join(format!("quack-test-{}.bin", std::process::id()));
+        store_cache(&path, &key, &tokens).unwrap();
+        assert_eq!(load_cache(&path, &key), Some(tokens));
+
+        // A mismatched header invalidates the cache — including a different
+        // model identity with otherwise-identical mtime/context.
+        let other = CacheKey {
+            model: "modelb".to_string(),
+            model_mtime: 42,
+            n_ctx: 4096,
+            prefix_hash: 0xdead_beef,
+        };
+        assert_eq!(load_cache(&path, &other), None);
+        let _ = fs::remove_file(&path);
+    }
+}